@@ -1,16 +1,603 @@
-use crate::audio_capture::AudioCaptureState;
+use crate::audio_capture::{
+    AudioCaptureState, CaptureSession, CaptureSource, DeviceInfo, OutputFormat, SampleFormat,
+    SinkList, StopRequest,
+};
+use arc_swap::ArcSwap;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
 
+/// How often the device-watch checks that the active input device is still
+/// present, so a mid-recording unplug is noticed in bounded time.
+const DEVICE_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn list_input_devices() -> Vec<DeviceInfo> {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+
+    let Ok(devices) = host.input_devices() else {
+        return Vec::new();
+    };
+
+    devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            Some(DeviceInfo {
+                is_default: Some(&name) == default_name.as_ref(),
+                id: name.clone(),
+                name,
+            })
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn start_capture(
     state: &AudioCaptureState,
     max_duration_secs: u32,
+    source: CaptureSource,
+    target: SampleFormat,
+    device_id: Option<String>,
+    output_format: OutputFormat,
 ) -> Result<(), String> {
-    todo!("implement Linux audio capture")
+    // `downmix_to_mono` always collapses to a single channel regardless of
+    // `target.channels`, so a non-mono target would have every encoder and
+    // sink told it's N-channel interleaved data while the buffer is actually
+    // mono. Reject it up front rather than silently mismetadata-ing the output.
+    if target.channels != 1 {
+        return Err(format!(
+            "unsupported target channel count {}: captures are always downmixed to mono",
+            target.channels
+        ));
+    }
+
+    let mut session = state.session.lock().unwrap();
+    if session.is_some() {
+        return Err("a capture is already in progress".to_string());
+    }
+
+    let device_id = {
+        let mut stored = state.device_id.lock().unwrap();
+        if device_id.is_some() {
+            *stored = device_id.clone();
+        }
+        device_id.or_else(|| stored.clone())
+    };
+
+    let buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
+    let (stop_tx, stop_rx) = mpsc::channel::<StopRequest>();
+    let (ready_tx, ready_rx) = oneshot::channel::<Result<String, String>>();
+
+    let thread_buffer = buffer.clone();
+    let level = state.level.clone();
+    let sinks = state.sinks.clone();
+    thread::spawn(move || {
+        run_capture_thread(
+            thread_buffer,
+            level,
+            sinks,
+            source,
+            target,
+            device_id,
+            stop_rx,
+            ready_tx,
+        )
+    });
+
+    let device_name = ready_rx
+        .await
+        .map_err(|_| "capture thread exited before it was ready".to_string())??;
+
+    let active = Arc::new(AtomicBool::new(true));
+    let disconnected = Arc::new(AtomicBool::new(false));
+
+    *session = Some(CaptureSession {
+        buffer,
+        sample_rate: target.rate,
+        channels: target.channels,
+        source,
+        stop: stop_tx.clone(),
+        active: active.clone(),
+        disconnected: disconnected.clone(),
+        output_format,
+    });
+    drop(session);
+
+    // The capture thread blocks on `stop_rx` until someone sends a `StopRequest`,
+    // so an auto-stop (and the device-watch below) is just another sender
+    // racing `stop_capture` to send one.
+    let auto_stop_tx = stop_tx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(max_duration_secs as u64)).await;
+        let (ack_tx, _ack_rx) = oneshot::channel();
+        let _ = auto_stop_tx.send(StopRequest { ack: ack_tx });
+    });
+
+    tokio::spawn(watch_device(device_name, active, disconnected, stop_tx));
+
+    Ok(())
 }
 
 pub async fn stop_capture(state: &AudioCaptureState) -> Result<String, String> {
-    todo!("implement Linux audio capture stop")
+    let session = state
+        .session
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| "no capture in progress".to_string())?;
+
+    // Tell the device-watch it can stop polling: this is a normal stop.
+    session.active.store(false, Ordering::Relaxed);
+
+    let (ack_tx, ack_rx) = oneshot::channel();
+    let _ = session.stop.send(StopRequest { ack: ack_tx });
+    // Wait for the capture thread to drop its stream so the buffer below is final.
+    let _ = ack_rx.await;
+
+    let samples = session.buffer.lock().unwrap().clone();
+    let path = std::env::temp_dir().join(format!(
+        "voicebox-capture-{}.{}",
+        std::process::id(),
+        session.output_format.extension()
+    ));
+
+    match session.output_format {
+        OutputFormat::Wav => encode_wav(&path, &samples, session.sample_rate, session.channels)?,
+        OutputFormat::Flac => encode_flac(&path, &samples, session.sample_rate, session.channels)?,
+        OutputFormat::Ogg => encode_ogg(&path, &samples, session.sample_rate, session.channels)?,
+        OutputFormat::Mp3 => encode_mp3(&path, &samples, session.sample_rate, session.channels)?,
+    }
+
+    if session.disconnected.load(Ordering::Relaxed) {
+        return Err(format!(
+            "input device disconnected mid-recording; partial recording flushed to {}",
+            path.to_string_lossy()
+        ));
+    }
+
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Polls for the active input device disappearing (e.g. unplugged) and, if it
+/// does, marks the session as disconnected and stops the capture the same
+/// way `stop_capture` would, so the callback thread is torn down promptly
+/// instead of being left recording from a dead device.
+async fn watch_device(
+    device_name: String,
+    active: Arc<AtomicBool>,
+    disconnected: Arc<AtomicBool>,
+    stop_tx: mpsc::Sender<StopRequest>,
+) {
+    while active.load(Ordering::Relaxed) {
+        tokio::time::sleep(DEVICE_WATCH_INTERVAL).await;
+        if !active.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let still_present = cpal::default_host()
+            .input_devices()
+            .map(|mut devices| devices.any(|d| d.name().is_ok_and(|n| n == device_name)))
+            .unwrap_or(false);
+
+        if !still_present {
+            disconnected.store(true, Ordering::Relaxed);
+            let (ack_tx, _ack_rx) = oneshot::channel();
+            let _ = stop_tx.send(StopRequest { ack: ack_tx });
+            return;
+        }
+    }
 }
 
 pub fn is_supported() -> bool {
-    false
+    cpal::default_host().default_input_device().is_some()
+}
+
+fn encode_wav(path: &Path, samples: &[f32], sample_rate: u32, channels: u16) -> Result<(), String> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer =
+        hound::WavWriter::create(path, spec).map_err(|err| format!("failed to create wav file: {err}"))?;
+    for &sample in samples {
+        writer
+            .write_sample(sample)
+            .map_err(|err| format!("failed to write wav sample: {err}"))?;
+    }
+    writer
+        .finalize()
+        .map_err(|err| format!("failed to finalize wav file: {err}"))
+}
+
+fn encode_flac(path: &Path, samples: &[f32], sample_rate: u32, channels: u16) -> Result<(), String> {
+    let ints: Vec<i32> = samples
+        .iter()
+        .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i32)
+        .collect();
+
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, err)| format!("invalid flac encoder config: {err:?}"))?;
+    let source =
+        flacenc::source::MemSource::from_samples(&ints, channels as usize, 16, sample_rate as usize);
+    let flac_stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|err| format!("flac encode failed: {err:?}"))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    flac_stream
+        .write(&mut sink)
+        .map_err(|err| format!("failed to serialize flac stream: {err:?}"))?;
+    std::fs::write(path, sink.as_slice()).map_err(|err| format!("failed to write flac file: {err}"))
+}
+
+fn encode_ogg(path: &Path, samples: &[f32], sample_rate: u32, channels: u16) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|err| format!("failed to create ogg file: {err}"))?;
+    let sample_rate = std::num::NonZeroU32::new(sample_rate)
+        .ok_or_else(|| "sample rate must be non-zero".to_string())?;
+    let channels =
+        std::num::NonZeroU8::new(channels as u8).ok_or_else(|| "channels must be non-zero".to_string())?;
+
+    let mut encoder = vorbis_rs::VorbisEncoderBuilder::new(sample_rate, channels, file)
+        .map_err(|err| format!("failed to create ogg encoder: {err}"))?
+        .build()
+        .map_err(|err| format!("failed to build ogg encoder: {err}"))?;
+
+    let channel_count = channels.get() as usize;
+    // Drop any trailing partial frame so every chunk below has exactly
+    // `channel_count` samples; otherwise the last per-channel buffer would
+    // come up short and `encode_audio_block` would see uneven channel lengths.
+    let usable_len = samples.len() - samples.len() % channel_count;
+    let samples = &samples[..usable_len];
+    let mut planar: Vec<Vec<f32>> = vec![Vec::new(); channel_count];
+    for frame in samples.chunks(channel_count) {
+        for (channel, &sample) in planar.iter_mut().zip(frame) {
+            channel.push(sample);
+        }
+    }
+    let planar_refs: Vec<&[f32]> = planar.iter().map(Vec::as_slice).collect();
+
+    encoder
+        .encode_audio_block(&planar_refs)
+        .map_err(|err| format!("ogg encode failed: {err}"))?;
+    encoder
+        .finish()
+        .map_err(|err| format!("failed to finalize ogg file: {err}"))?;
+    Ok(())
+}
+
+fn encode_mp3(path: &Path, samples: &[f32], sample_rate: u32, channels: u16) -> Result<(), String> {
+    let mut builder =
+        mp3lame_encoder::Builder::new().ok_or_else(|| "failed to create mp3 encoder".to_string())?;
+    builder
+        .set_num_channels(channels as u8)
+        .map_err(|err| format!("failed to set mp3 channel count: {err:?}"))?;
+    builder
+        .set_sample_rate(sample_rate)
+        .map_err(|err| format!("failed to set mp3 sample rate: {err:?}"))?;
+    builder
+        .set_quality(mp3lame_encoder::Quality::Best)
+        .map_err(|err| format!("failed to set mp3 quality: {err:?}"))?;
+    let mut encoder = builder
+        .build()
+        .map_err(|err| format!("failed to build mp3 encoder: {err:?}"))?;
+
+    let mut out = vec![0u8; mp3lame_encoder::max_required_buffer_size(samples.len())];
+    let written = if channels <= 1 {
+        encoder
+            .encode(mp3lame_encoder::MonoPcm(samples), out.as_mut_slice())
+            .map_err(|err| format!("mp3 encode failed: {err:?}"))?
+    } else {
+        let mut left = Vec::with_capacity(samples.len() / 2);
+        let mut right = Vec::with_capacity(samples.len() / 2);
+        for frame in samples.chunks(channels as usize) {
+            left.push(frame[0]);
+            right.push(*frame.get(1).unwrap_or(&frame[0]));
+        }
+        encoder
+            .encode(
+                mp3lame_encoder::DualPcm { left: &left, right: &right },
+                out.as_mut_slice(),
+            )
+            .map_err(|err| format!("mp3 encode failed: {err:?}"))?
+    };
+    out.truncate(written);
+
+    let mut file = std::fs::File::create(path).map_err(|err| format!("failed to create mp3 file: {err}"))?;
+    use std::io::Write as _;
+    file.write_all(&out)
+        .map_err(|err| format!("failed to write mp3 file: {err}"))
+}
+
+/// Finds the PulseAudio/PipeWire monitor source for the default sink, so
+/// `CaptureSource::SystemLoopback` records whatever the system is playing
+/// out rather than the microphone. Prefers the monitor that names the
+/// default sink explicitly, falling back to any monitor device present.
+fn find_loopback_device(host: &cpal::Host) -> Result<cpal::Device, String> {
+    if let Some(sink_name) = host.default_output_device().and_then(|d| d.name().ok()) {
+        let target = format!("{sink_name}.monitor");
+        let devices = host
+            .input_devices()
+            .map_err(|err| format!("failed to enumerate input devices: {err}"))?;
+        if let Some(device) = devices.into_iter().find(|d| d.name().is_ok_and(|n| n == target)) {
+            return Ok(device);
+        }
+    }
+
+    let devices = host
+        .input_devices()
+        .map_err(|err| format!("failed to enumerate input devices: {err}"))?;
+    devices
+        .into_iter()
+        .find(|d| d.name().is_ok_and(|n| n.ends_with(".monitor")))
+        .ok_or_else(|| {
+            "no PulseAudio/PipeWire monitor source found for system-audio capture".to_string()
+        })
+}
+
+/// Owns the cpal stream for the lifetime of a capture. Runs on its own thread
+/// because `cpal::Stream` is not `Send`, so it can't just live in `AudioCaptureState`.
+#[allow(clippy::too_many_arguments)]
+fn run_capture_thread(
+    buffer: Arc<Mutex<Vec<f32>>>,
+    level: Arc<AtomicU32>,
+    sinks: Arc<ArcSwap<SinkList>>,
+    source: CaptureSource,
+    target: SampleFormat,
+    device_id: Option<String>,
+    stop_rx: mpsc::Receiver<StopRequest>,
+    ready_tx: oneshot::Sender<Result<String, String>>,
+) {
+    let host = cpal::default_host();
+    let device = match source {
+        CaptureSource::Microphone => match device_id {
+            Some(id) => host
+                .input_devices()
+                .map_err(|err| format!("failed to enumerate input devices: {err}"))
+                .and_then(|mut devices| {
+                    devices
+                        .find(|d| d.name().is_ok_and(|n| n == id))
+                        .ok_or_else(|| format!("input device '{id}' not found"))
+                }),
+            None => host
+                .default_input_device()
+                .ok_or_else(|| "no default input device".to_string()),
+        },
+        CaptureSource::SystemLoopback => find_loopback_device(&host),
+    };
+    let device = match device {
+        Ok(device) => device,
+        Err(err) => {
+            let _ = ready_tx.send(Err(err));
+            return;
+        }
+    };
+    let device_name = device.name().unwrap_or_else(|_| "unknown device".to_string());
+    let config = match device.default_input_config() {
+        Ok(config) => config,
+        Err(err) => {
+            let _ = ready_tx.send(Err(format!("failed to read input config: {err}")));
+            return;
+        }
+    };
+
+    let sample_format = config.sample_format();
+    let stream_config: cpal::StreamConfig = config.into();
+
+    let stream = match build_input_stream(
+        &device,
+        &stream_config,
+        sample_format,
+        buffer,
+        level,
+        sinks,
+        target,
+    ) {
+        Ok(stream) => stream,
+        Err(err) => {
+            let _ = ready_tx.send(Err(err));
+            return;
+        }
+    };
+
+    if let Err(err) = stream.play() {
+        let _ = ready_tx.send(Err(format!("failed to start input stream: {err}")));
+        return;
+    }
+
+    let _ = ready_tx.send(Ok(device_name));
+
+    if let Ok(request) = stop_rx.recv() {
+        drop(stream);
+        let _ = request.ack.send(());
+    }
+}
+
+fn build_input_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    buffer: Arc<Mutex<Vec<f32>>>,
+    level: Arc<AtomicU32>,
+    sinks: Arc<ArcSwap<SinkList>>,
+    target: SampleFormat,
+) -> Result<cpal::Stream, String> {
+    let err_fn = |err| eprintln!("audio capture stream error: {err}");
+    let native_channels = config.channels;
+    let native_rate = config.sample_rate.0;
+
+    match sample_format {
+        cpal::SampleFormat::F32 => {
+            let mut normalizer = Normalizer::new(native_rate, target.rate);
+            device
+                .build_input_stream(
+                    config,
+                    move |data: &[f32], _: &_| {
+                        let mono = downmix_to_mono(data, native_channels);
+                        let frames = normalizer.process(&mono);
+                        publish_peak(&level, frames.iter().copied());
+                        publish_frames(&sinks, target.rate, target.channels, &frames);
+                        buffer.lock().unwrap().extend(frames);
+                    },
+                    err_fn,
+                    None,
+                )
+                .map_err(|err| format!("failed to build input stream: {err}"))
+        }
+        cpal::SampleFormat::I16 => {
+            let mut normalizer = Normalizer::new(native_rate, target.rate);
+            device
+                .build_input_stream(
+                    config,
+                    move |data: &[i16], _: &_| {
+                        let float: Vec<f32> =
+                            data.iter().map(|&s| s as f32 / i16::MAX as f32).collect();
+                        let mono = downmix_to_mono(&float, native_channels);
+                        let frames = normalizer.process(&mono);
+                        publish_peak(&level, frames.iter().copied());
+                        publish_frames(&sinks, target.rate, target.channels, &frames);
+                        buffer.lock().unwrap().extend(frames);
+                    },
+                    err_fn,
+                    None,
+                )
+                .map_err(|err| format!("failed to build input stream: {err}"))
+        }
+        other => Err(format!("unsupported input sample format: {other:?}")),
+    }
+}
+
+/// Averages `channels` interleaved channels down to mono. A no-op copy when
+/// the source is already mono.
+fn downmix_to_mono(data: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return data.to_vec();
+    }
+    let channels = channels as usize;
+    data.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Resamples a mono stream to `out_rate` by linear interpolation between the
+/// two bracketing input samples. Carries the fractional cursor and the last
+/// input sample across calls so chunk boundaries don't introduce clicks.
+struct Normalizer {
+    step: f64,
+    pos: f64,
+    prev: f32,
+    primed: bool,
+}
+
+impl Normalizer {
+    fn new(in_rate: u32, out_rate: u32) -> Self {
+        Self {
+            step: in_rate as f64 / out_rate as f64,
+            pos: 0.0,
+            prev: 0.0,
+            primed: false,
+        }
+    }
+
+    fn process(&mut self, mono_in: &[f32]) -> Vec<f32> {
+        if mono_in.is_empty() {
+            return Vec::new();
+        }
+        if !self.primed {
+            self.prev = mono_in[0];
+            self.primed = true;
+        }
+
+        let mut out = Vec::new();
+        loop {
+            let whole = self.pos.floor() as isize;
+            if whole + 1 >= mono_in.len() as isize {
+                break;
+            }
+            let a = if whole < 0 { self.prev } else { mono_in[whole as usize] };
+            let b = mono_in[(whole + 1) as usize];
+            let frac = (self.pos - self.pos.floor()) as f32;
+            out.push(a + (b - a) * frac);
+            self.pos += self.step;
+        }
+
+        self.pos -= mono_in.len() as f64;
+        self.prev = *mono_in.last().unwrap();
+        out
+    }
+}
+
+/// Hands `frames` to every registered [`crate::audio_capture::CaptureSink`]
+/// without taking a lock: `sinks.load()` is a single atomic pointer read, so
+/// registering or dropping a sink elsewhere never stalls the audio callback.
+fn publish_frames(sinks: &ArcSwap<SinkList>, sample_rate: u32, channels: u16, frames: &[f32]) {
+    for (_, sink) in sinks.load().iter() {
+        sink.on_frames(sample_rate, channels, frames);
+    }
+}
+
+/// Publishes the peak absolute sample in `samples` to `level` without ever
+/// blocking the audio callback: a `fetch_update` max so a transient spike
+/// from one buffer is never clobbered by a quieter one read just after it.
+fn publish_peak(level: &AtomicU32, samples: impl Iterator<Item = f32>) {
+    let peak = samples.fold(0.0f32, |acc, sample| acc.max(sample.abs()));
+    let _ = level.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        let current = f32::from_bits(bits);
+        Some(current.max(peak).to_bits())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{downmix_to_mono, publish_peak, Normalizer};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A 2x upsample of a ramp split across two chunks should line up with
+    /// the un-shifted ramp positions, including across the chunk boundary.
+    #[test]
+    fn normalizer_upsamples_ramp_without_shifting_positions() {
+        let mut normalizer = Normalizer::new(1, 2);
+
+        let first = normalizer.process(&[0.0, 2.0, 4.0]);
+        let second = normalizer.process(&[6.0, 8.0, 10.0]);
+
+        assert_eq!(first, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(second, vec![4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+    }
+
+    #[test]
+    fn downmix_to_mono_is_a_noop_for_mono_input() {
+        let data = [0.1, -0.2, 0.3];
+        assert_eq!(downmix_to_mono(&data, 1), data.to_vec());
+    }
+
+    #[test]
+    fn downmix_to_mono_averages_interleaved_channels() {
+        let stereo = [1.0, 3.0, -1.0, -1.0];
+        assert_eq!(downmix_to_mono(&stereo, 2), vec![2.0, -1.0]);
+    }
+
+    #[test]
+    fn publish_peak_keeps_the_highest_magnitude_seen() {
+        let level = AtomicU32::new(0.0f32.to_bits());
+
+        publish_peak(&level, [0.1, -0.2].into_iter());
+        assert_eq!(f32::from_bits(level.load(Ordering::Relaxed)), 0.2);
+
+        publish_peak(&level, [0.05].into_iter());
+        assert_eq!(
+            f32::from_bits(level.load(Ordering::Relaxed)),
+            0.2,
+            "a quieter buffer must not clobber a louder peak"
+        );
+
+        publish_peak(&level, [0.9].into_iter());
+        assert_eq!(f32::from_bits(level.load(Ordering::Relaxed)), 0.9);
+    }
 }
@@ -0,0 +1,254 @@
+pub mod linux;
+
+use arc_swap::ArcSwap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Per-read decay applied to the live level meter so it falls back to silence
+/// smoothly instead of holding the last peak forever.
+const LEVEL_DECAY: f32 = 0.85;
+
+/// A live tap onto the capture pipeline, registered with [`add_capture_sink`].
+/// Called from the audio callback, so implementations must not block.
+pub trait CaptureSink: Send + Sync {
+    fn on_frames(&self, sample_rate: u32, channels: u16, frames: &[f32]);
+}
+
+pub(crate) type SinkList = Vec<(u64, Arc<dyn CaptureSink>)>;
+
+/// Shared state for the currently active audio capture, if any.
+///
+/// The platform backend's `start_capture` populates `session` and `stop_capture`
+/// takes it back out, so there is never more than one capture in flight.
+#[derive(Default)]
+pub struct AudioCaptureState {
+    pub(crate) session: Mutex<Option<CaptureSession>>,
+    /// Bit-cast of the current peak level (`f32::to_bits`), updated lock-free
+    /// from the capture callback and decayed lazily on read.
+    pub(crate) level: Arc<AtomicU32>,
+    /// Registered live-PCM sinks, read by the audio callback on every buffer
+    /// without ever taking a lock; writers (`add_capture_sink`/`SinkHandle::drop`)
+    /// install a whole new list via read-copy-update instead of mutating in place.
+    pub(crate) sinks: Arc<ArcSwap<SinkList>>,
+    next_sink_id: AtomicU64,
+    /// Id of the input device the caller last asked for, remembered so a
+    /// later `start_capture` without an explicit id reuses the same choice.
+    pub(crate) device_id: Mutex<Option<String>>,
+}
+
+/// Registers `sink` to receive every live PCM buffer produced while a capture
+/// is running, in addition to the final file written by `stop_capture`.
+/// Dropping the returned handle unregisters it.
+pub fn add_capture_sink(state: &AudioCaptureState, sink: impl CaptureSink + 'static) -> SinkHandle {
+    let id = state.next_sink_id.fetch_add(1, Ordering::Relaxed);
+    let sink: Arc<dyn CaptureSink> = Arc::new(sink);
+    state.sinks.rcu(move |current| {
+        let mut next = (**current).clone();
+        next.push((id, sink.clone()));
+        next
+    });
+    SinkHandle {
+        id,
+        sinks: state.sinks.clone(),
+    }
+}
+
+/// Handle to a registered [`CaptureSink`]; unregisters on drop.
+pub struct SinkHandle {
+    id: u64,
+    sinks: Arc<ArcSwap<SinkList>>,
+}
+
+impl Drop for SinkHandle {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.sinks.rcu(move |current| {
+            let mut next = (**current).clone();
+            next.retain(|(sink_id, _)| *sink_id != id);
+            next
+        });
+    }
+}
+
+/// Reads the current input level (0.0 = silence) and decays the held value
+/// so a meter polling this repeatedly falls smoothly once input goes quiet,
+/// rather than sticking at the last peak.
+pub fn current_level(state: &AudioCaptureState) -> f32 {
+    // A plain load-then-store would race `publish_peak`'s fetch_update: a
+    // fresh peak landing between our load and our store would be clobbered
+    // by a decayed copy of the stale value we read. Doing the decay itself
+    // as a fetch_update makes read-and-decay atomic with respect to it.
+    let previous = state
+        .level
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f32::from_bits(bits) * LEVEL_DECAY).to_bits())
+        })
+        .expect("closure always returns Some, so fetch_update never fails");
+    f32::from_bits(previous)
+}
+
+/// Everything a backend needs to hand a running capture back to the shared state:
+/// the buffer its callback is filling, the format it is filling it in, and a way
+/// to ask it to stop.
+pub(crate) struct CaptureSession {
+    pub buffer: Arc<Mutex<Vec<f32>>>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub source: CaptureSource,
+    pub stop: Sender<StopRequest>,
+    /// Cleared by `stop_capture` so the backend's device-watch knows to give
+    /// up once the capture has ended normally.
+    pub active: Arc<AtomicBool>,
+    /// Set by the backend's device-watch if the active input device
+    /// disappears mid-recording, so `stop_capture` can report that distinctly
+    /// instead of returning a normal success once the caller stops it.
+    pub disconnected: Arc<AtomicBool>,
+    pub output_format: OutputFormat,
+}
+
+/// Container/codec `stop_capture` encodes the recording into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Lossless, uncompressed; the default so capture never depends on an
+    /// external encoder being available.
+    #[default]
+    Wav,
+    /// Lossless and far smaller than WAV for long recordings.
+    Flac,
+    /// Lossy, open, and small.
+    Ogg,
+    /// Lossy; maximizes compatibility with tools that don't speak Ogg.
+    Mp3,
+}
+
+impl OutputFormat {
+    /// File extension `stop_capture` should use for a recording in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Ogg => "ogg",
+            OutputFormat::Mp3 => "mp3",
+        }
+    }
+}
+
+/// One input device as reported by the platform backend's `list_input_devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// Target format captures are normalized to before being buffered. Speech
+/// recognizers almost universally expect 16 kHz mono, which is also what
+/// [`Default`] gives you.
+///
+/// `channels` must currently be `1`: the capture pipeline always downmixes
+/// to mono, and `start_capture` rejects any other value rather than lying
+/// about the channel count to encoders and sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleFormat {
+    pub rate: u32,
+    pub channels: u16,
+}
+
+impl Default for SampleFormat {
+    fn default() -> Self {
+        Self {
+            rate: 16_000,
+            channels: 1,
+        }
+    }
+}
+
+/// Which input a capture reads from. Both sources are recorded through the
+/// same pipeline, so the rest of `AudioCaptureState` doesn't need to care
+/// which one is active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureSource {
+    /// The default (or user-selected) microphone.
+    #[default]
+    Microphone,
+    /// Whatever the system is currently playing out, captured via the
+    /// platform's loopback/monitor device.
+    SystemLoopback,
+}
+
+/// Sent on `CaptureSession::stop` to tear the capture down; the sender awaits
+/// `ack` until the backend confirms its stream has actually stopped producing
+/// frames, so the buffer can be read without racing the callback. `ack` is a
+/// `oneshot` rather than `std::sync::mpsc` so callers on the async side can
+/// `.await` it instead of blocking a Tokio worker thread on `recv()`.
+pub(crate) struct StopRequest {
+    pub ack: oneshot::Sender<()>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_level_decays_and_returns_the_pre_decay_value() {
+        let state = AudioCaptureState::default();
+        state.level.store(1.0f32.to_bits(), Ordering::Relaxed);
+
+        assert_eq!(current_level(&state), 1.0);
+        assert_eq!(
+            f32::from_bits(state.level.load(Ordering::Relaxed)),
+            LEVEL_DECAY
+        );
+        assert_eq!(current_level(&state), LEVEL_DECAY);
+    }
+
+    #[test]
+    fn output_format_extension_matches_each_variant() {
+        assert_eq!(OutputFormat::Wav.extension(), "wav");
+        assert_eq!(OutputFormat::Flac.extension(), "flac");
+        assert_eq!(OutputFormat::Ogg.extension(), "ogg");
+        assert_eq!(OutputFormat::Mp3.extension(), "mp3");
+    }
+
+    struct CountingSink {
+        calls: Arc<AtomicU64>,
+    }
+
+    impl CaptureSink for CountingSink {
+        fn on_frames(&self, _sample_rate: u32, _channels: u16, _frames: &[f32]) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn publish_to_sinks(state: &AudioCaptureState) {
+        for (_, sink) in state.sinks.load().iter() {
+            sink.on_frames(16_000, 1, &[0.0]);
+        }
+    }
+
+    #[test]
+    fn sink_receives_frames_until_its_handle_is_dropped() {
+        let state = AudioCaptureState::default();
+        let calls = Arc::new(AtomicU64::new(0));
+
+        let handle = add_capture_sink(
+            &state,
+            CountingSink {
+                calls: calls.clone(),
+            },
+        );
+        publish_to_sinks(&state);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        drop(handle);
+        assert!(state.sinks.load().is_empty());
+        publish_to_sinks(&state);
+        assert_eq!(
+            calls.load(Ordering::Relaxed),
+            1,
+            "a dropped handle must stop receiving frames"
+        );
+    }
+}